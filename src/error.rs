@@ -0,0 +1,21 @@
+//! The error type the crawler surfaces instead of `.unwrap()`-ing and `println!`-ing as it goes.
+//! Fetching, status validation and URL parsing can each fail for unrelated reasons, so we model
+//! them as distinct variants and let callers decide what to do; the link-checker mode in
+//! particular records these per URL so it can report exactly why a link is considered broken.
+
+use reqwest::StatusCode;
+use thiserror::Error;
+
+/// Something that went wrong while fetching, validating or parsing a single URL.
+#[derive(Debug, Error)]
+pub enum CrawlError {
+    /// The underlying request failed (DNS, connection, timeout, too many redirects, ...).
+    #[error("request failed: {0}")]
+    ReqwestError(#[from] reqwest::Error),
+    /// The server responded, but with a status we don't accept (see `Configuration::accepted`).
+    #[error("unexpected status code: {0}")]
+    BadStatus(StatusCode),
+    /// A URL could not be parsed.
+    #[error("could not parse url: {0}")]
+    ParseError(#[from] url::ParseError),
+}
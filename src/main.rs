@@ -1,38 +1,200 @@
-use reqwest::{Client, Response};
+use reqwest::{Client, Response, StatusCode};
 use scraper::{Html, Selector};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::env::args;
 use std::process::exit;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex};
-use std::thread::sleep;
-use std::time::{Duration, SystemTime};
-use tokio::sync::Semaphore;
+use std::time::{Duration, Instant, SystemTime};
+use tokio::sync::mpsc::{self, UnboundedSender};
+use tokio::sync::{Notify, Semaphore};
 use tokio::task::JoinSet;
+use url::Url;
+
+mod error;
+mod output;
+mod robots;
+use error::CrawlError;
+use output::{write_graph, OutputFormat};
+use robots::Rules;
+
+/// The shared map of every link we've validated to its outcome: `Ok` for an accepted status,
+/// `Err` for a request failure or an unaccepted status. Populated only in link-checker mode.
+type LinkResults = Arc<Mutex<HashMap<String, Result<StatusCode, CrawlError>>>>;
+/// The inverse of the `urls` adjacency map: each link to the set of pages that linked to it, so a
+/// broken-link report can name the parents that need fixing.
+type Parents = Arc<Mutex<HashMap<String, HashSet<String>>>>;
+
+/// The User-Agent we present on every request. Many sites (e.g. those behind Cloudflare) block
+/// obvious scrapers, so we masquerade as a mobile browser. robots.txt `User-agent` groups are
+/// matched against [`CRAWLER_PRODUCT_TOKEN`], not this string.
+const USER_AGENT: &str = "Mozilla/5.0 (iPad; CPU OS 12_2 like Mac OS X) AppleWebKit/605.1.15 (KHTML, like Gecko) Mobile/15E148";
+
+/// Our own product token, used solely to select the matching robots.txt `User-agent` group. It is
+/// deliberately distinct from the browser [`USER_AGENT`] we send on requests so that robots group
+/// selection keys off our identity rather than substrings of the masquerade string.
+const CRAWLER_PRODUCT_TOKEN: &str = "crawler";
+
+/// Crawler configuration knobs, mirroring the `Configuration` surface the `spider` crate exposes.
+struct Configuration {
+    // Whether to fetch and obey /robots.txt (Disallow/Allow and Crawl-delay) for the root host.
+    respect_robots_txt: bool,
+    // Whether to follow links on same-registrable-domain subdomains, rather than the root host only.
+    subdomains: bool,
+    // How many 3xx redirect hops to follow before giving up on a URL. 301/302 responses are
+    // extremely common (canonicalization, trailing-slash fixes) so we follow them rather than
+    // treating them as dead ends.
+    max_redirects: usize,
+    // Link-checker mode (the `--check` flag): off-domain links are validated with a HEAD/GET
+    // rather than crawled, and a broken-link report is printed at the end.
+    check: bool,
+    // Status codes to accept in addition to the 2xx range when deciding whether a link is broken,
+    // following lychee's `accepted` set idea.
+    accepted: Vec<StatusCode>,
+    // The (CSS selector, attribute) pairs to pull links out of, like the element selectors the
+    // voyager crate exposes. Defaults to `a[href]`; add e.g. `("link", "href")`, `("img", "src")`
+    // to also follow stylesheets, images and scripts.
+    extractors: Vec<(String, String)>,
+    // Stop enqueuing URLs more than this many link-hops from the root. `None` means unbounded.
+    max_depth: Option<usize>,
+    // Cap on the total number of pages fetched. `None` means unbounded.
+    max_pages: Option<usize>,
+    // How to serialize the crawled link graph (`--format`).
+    format: OutputFormat,
+    // File to write the output to (`--output`); `None` streams to stdout.
+    output: Option<String>,
+}
+
+impl Default for Configuration {
+    fn default() -> Configuration {
+        Configuration {
+            respect_robots_txt: true,
+            subdomains: false,
+            max_redirects: 10,
+            check: false,
+            accepted: vec![],
+            extractors: vec![("a".to_string(), "href".to_string())],
+            max_depth: None,
+            max_pages: None,
+            format: OutputFormat::Text,
+            output: None,
+        }
+    }
+}
+
+/// Whether `status` counts as a healthy link: any 2xx, or a status in the configured allow-list.
+/// Anything else is reported as a [`CrawlError::BadStatus`].
+fn classify(status: StatusCode, accepted: &[StatusCode]) -> Result<StatusCode, CrawlError> {
+    if status.is_success() || accepted.contains(&status) {
+        Ok(status)
+    } else {
+        Err(CrawlError::BadStatus(status))
+    }
+}
+
+/// Build the shared HTTP client. A persistent cookie store is enabled so session cookies set on a
+/// first response (e.g. behind a login or consent wall) are replayed on later requests, and the
+/// redirect policy follows up to `max_redirects` hops so 3xx responses resolve to their target
+/// rather than being dropped.
+fn build_client(max_redirects: usize) -> Client {
+    Client::builder()
+        .cookie_store(true)
+        .redirect(reqwest::redirect::Policy::limited(max_redirects))
+        .build()
+        .expect("Could not build HTTP client")
+}
+
+/// Read the next argument as the `usize` value of a numeric flag, exiting with an input error if it
+/// is missing or doesn't parse.
+fn parse_usize_flag(args: &mut impl Iterator<Item = String>, flag: &str) -> usize {
+    let value = args.next().unwrap_or_else(|| {
+        println!("Input error: {flag} requires a number.");
+        exit(1)
+    });
+    value.parse().unwrap_or_else(|_| {
+        println!("Input error: {flag} expects a non-negative integer, got {value:?}.");
+        exit(1)
+    })
+}
 
 #[tokio::main]
 async fn main() {
     // Mark the start time of the crawl, so we can measure how long it takes.
     let now = SystemTime::now();
-    let args: Vec<String> = args().collect();
-    if args.len() != 2 {
-        println!("Input error: Incorrect number of arguments provided, precisely one argument should be given.");
+    // Split the arguments into flags and the single positional URL. `--check` turns the crawler
+    // into a site-wide broken-link validator; `--format` and `--output` control how the link graph
+    // is serialized.
+    let mut check = false;
+    let mut format = OutputFormat::Text;
+    let mut output = None;
+    let mut extra_extractors: Vec<(String, String)> = vec![];
+    let mut max_depth = None;
+    let mut max_pages = None;
+    let mut positional: Vec<String> = vec![];
+    let mut args = args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--check" => check = true,
+            "--extract" => {
+                // Two values: the CSS selector and the attribute to read off it, e.g.
+                // `--extract img src` to also follow image resources.
+                let selector = args.next().unwrap_or_else(|| {
+                    println!("Input error: --extract requires a selector and an attribute.");
+                    exit(1)
+                });
+                let attr = args.next().unwrap_or_else(|| {
+                    println!("Input error: --extract requires a selector and an attribute.");
+                    exit(1)
+                });
+                extra_extractors.push((selector, attr));
+            }
+            "--max-depth" => max_depth = Some(parse_usize_flag(&mut args, "--max-depth")),
+            "--max-pages" => max_pages = Some(parse_usize_flag(&mut args, "--max-pages")),
+            "--format" => {
+                let value = args.next().unwrap_or_else(|| {
+                    println!("Input error: --format requires a value (text|json|dot).");
+                    exit(1)
+                });
+                format = OutputFormat::parse(&value).unwrap_or_else(|| {
+                    println!("Input error: unknown --format {value:?} (expected text|json|dot).");
+                    exit(1)
+                });
+            }
+            "--output" => {
+                output = Some(args.next().unwrap_or_else(|| {
+                    println!("Input error: --output requires a file path.");
+                    exit(1)
+                }));
+            }
+            _ => positional.push(arg),
+        }
+    }
+    if positional.len() != 1 {
+        println!("Input error: Incorrect number of arguments provided, precisely one URL should be given.");
         exit(1)
     }
-    let origin_url = &args[1];
+    let origin_url = &positional[0];
 
-    let client = Client::new();
+    let client = build_client(Configuration::default().max_redirects);
     // Send GET request to check if server is returning 200s.
     // Many websites are protected by Cloudflare, which detects if a request is coming from a real user or a web scraper (that's us!).
     // If it detects that the request is coming from a web scraper, it will block the request and return a 403 Forbidden status code.
     // To avoid this, we can set the User-Agent header to a value that is commonly used by web browsers.
     // Interestingly, when I started writing this programme in python I didn't run into this problem - so the python requests library must be handling this under the covers!
-    let res = client.get(origin_url).header("User-Agent", "Mozilla/5.0 (iPad; CPU OS 12_2 like Mac OS X) AppleWebKit/605.1.15 (KHTML, like Gecko) Mobile/15E148").send().await.unwrap();
+    let res = client.get(origin_url).header("User-Agent", USER_AGENT).send().await.unwrap();
     println!("Status for {}: {}", origin_url, res.status());
     if res.status() == reqwest::StatusCode::ACCEPTED {
         println!("Target server is under load and returning 202s which we don't currently handle. Retry shortly.")
     }
 
-    let crawler = Crawler::new(origin_url.to_string());
+    let mut crawler = Crawler::new(origin_url.to_string());
+    crawler.config.check = check;
+    crawler.config.format = format;
+    crawler.config.output = output;
+    crawler.config.max_depth = max_depth;
+    crawler.config.max_pages = max_pages;
+    // Any --extract pairs are added on top of the default `a[href]` set.
+    crawler.config.extractors.extend(extra_extractors);
     // Crawl the server.
     crawler.crawl_whole().await;
 
@@ -51,153 +213,538 @@ struct Crawler {
     // An HTTP client, to be reused for each GET request.
     //Note don't need to wrap client in an Arc as the Client type already uses an Arc internally, therefore can be safely shared between threads
     client: Client,
-    // A store of outstanding URLs that need to be crawled
-    urls_to_visit: Arc<Mutex<Vec<String>>>,
     // A key value store of all URLs found on the page of a given URL
     urls: Arc<Mutex<HashMap<String, Vec<String>>>>,
+    // Tunable behaviour (robots.txt compliance, subdomain following, ...).
+    config: Configuration,
+    // The compiled robots.txt rules for the root host, consulted before enqueuing/fetching a URL.
+    robots: Arc<Rules>,
+    // The earliest time we're next allowed to fetch from each host, used to honour a robots.txt
+    // Crawl-delay by serializing same-host fetches `delay` apart.
+    next_fetch: Arc<Mutex<HashMap<String, Instant>>>,
 }
 
 impl Crawler {
     fn new(root: String) -> Crawler {
+        let config = Configuration::default();
         Crawler {
-            root: root.clone(),
-            client: Client::new(),
-            urls_to_visit: Arc::new(Mutex::new(vec![root])),
+            root,
+            client: build_client(config.max_redirects),
             urls: Arc::new(Mutex::new(HashMap::new())),
+            config,
+            robots: Arc::new(Rules::default()),
+            next_fetch: Arc::new(Mutex::new(HashMap::new())),
         }
     }
 
-    /// This function is responsible for crawling the whole website. It does this by spawning a number of tasks, each of which crawls an individual URL.
-    /// The function uses a while loop to repeatedly spawn tasks until there are no more URLs to visit.
-    /// It uses a JoinSet to wait for all the tasks to complete.
-    /// It uses a semaphore to limit the number of concurrent tasks that are spawned to avoid hitting the maximum number of open sockets.
+    /// This function is responsible for crawling the whole website using a producer/consumer design
+    /// built on an unbounded channel. Discovered URLs are sent onto the channel; this dispatcher pulls
+    /// them off and spawns a worker per URL (capped by a semaphore), and each worker sends the
+    /// URLs it finds back onto the channel. Outstanding work is tracked with an atomic in-flight
+    /// counter, so the crawl terminates deterministically the moment the channel is empty and no
+    /// worker is mid-fetch - no sleep primer and no lock held across the hot path.
     /// It prints out a map, with each URL as a key, and the value as a list of URLs that it links to.
-    async fn crawl_whole(self) {
+    async fn crawl_whole(mut self) {
+        // Fetch and compile the root host's robots.txt once, up front, if we've been asked to
+        // respect it. Every task below then shares these rules behind an Arc.
+        if self.config.respect_robots_txt {
+            if let Ok(root_url) = Url::parse(&self.root) {
+                self.robots = Arc::new(
+                    Rules::fetch(&self.client, &root_url, USER_AGENT, CRAWLER_PRODUCT_TOKEN).await,
+                );
+            }
+        }
+
         // There's an upper limit of how many outgoing TCP connections we can open at a given time (limited by how many sockets we can open)- if we try and spawn
         // more tokio tasks than this limit, we'll hit an error for having too many files open. Therefore, we limit the number of concurrent tasks spawned
         // using a semaphore. The semaphore is initialized with the number of concurrent tasks we want to allow, and each time we spawn a task, we acquire a permit
-        let sem = Arc::new(Semaphore::new(100));
+        let concurrency = 100;
+        let sem = Arc::new(Semaphore::new(concurrency));
+
+        // The work queue. Each item is a URL paired with its depth (link-hops from the root), so
+        // depth limiting can be enforced as URLs are discovered. Concurrency is bounded by the
+        // semaphore alone; the queue itself is unbounded so a worker never blocks on `send` while
+        // holding a permit. A bounded channel sized to the permit count would deadlock under high
+        // fan-out - every worker stuck mid-`send` on a full queue holds a permit, leaving the single
+        // dispatcher unable to acquire one to drain the queue and free a worker.
+        let (tx, mut rx) = mpsc::unbounded_channel::<(Url, usize)>();
+        // The set of URLs we've already enqueued, so nothing is sent onto the channel twice.
+        let seen: Arc<Mutex<HashSet<Url>>> = Arc::new(Mutex::new(HashSet::new()));
+        // Count of URLs that are either sitting in the channel or being actively crawled. When it
+        // hits zero the crawl is finished; the worker that drives it to zero wakes the dispatcher.
+        let in_flight = Arc::new(AtomicUsize::new(0));
+        let done = Arc::new(Notify::new());
+
+        // Link-checker state, used only when `--check` is set: the validated status of every link,
+        // and the inverse parent->child map so we can attribute broken links back to their pages.
+        let accepted = Arc::new(self.config.accepted.clone());
+        let link_results: LinkResults = Arc::new(Mutex::new(HashMap::new()));
+        let parents: Parents = Arc::new(Mutex::new(HashMap::new()));
+
+        // The selectors to extract links with, and the running count of pages fetched so far (used
+        // to enforce `max_pages`).
+        let extractors = Arc::new(self.config.extractors.clone());
+        let pages_fetched = Arc::new(AtomicUsize::new(0));
+
+        // Seed the queue with the root URL at depth 0. If the root doesn't parse there's nothing to
+        // crawl; return early rather than entering the dispatch loop, which would otherwise block
+        // forever on an empty queue that can never be notified done.
+        let root_url = match Url::parse(&self.root) {
+            Ok(root_url) => canonicalize(root_url),
+            Err(e) => {
+                eprintln!("Could not parse root URL {}: {}", self.root, e);
+                return;
+            }
+        };
+        seen.lock().expect("Could not obtain lock").insert(root_url.clone());
+        in_flight.fetch_add(1, Ordering::SeqCst);
+        tx.send((root_url, 0)).expect("Queue unexpectedly closed");
 
         // JoinSet is a helper struct that allows us to spawn a number of tasks and then wait for them all to complete.
         let mut set = JoinSet::new();
-        // Spawn an "initial task" that will sleep for 1 second. This is necessary so that we can initially enter the while loop immediately below.
-        set.spawn(async {
-            sleep(Duration::from_secs(1));
-        });
-
-        // Enter a while loop that will continue until there are no more URLs to visit. For that to be true, the `urls_to_visit` vector must be empty and
-        // all the tasks spawned must have completed. Use the `join_next` method on the JoinSet to check if there are any tasks that have not yet completed.
-        while set.join_next().await.is_some() || !self.urls_to_visit.lock().unwrap().is_empty() {
-            while !self
-                .urls_to_visit
-                .lock()
-                .expect("Count not obtain lock")
-                .is_empty()
-            {
-                // Aquire a permit from the semaphore, which will block if the number of concurrent tasks has reached the limit.
-                let permit = Arc::clone(&sem).acquire_owned().await;
-
-                // Pop off a URL from the urls_to_visit
-                let path = self
-                    .urls_to_visit
-                    .clone()
-                    .lock()
-                    .expect("Count not obtain lock")
-                    .pop()
-                    .unwrap();
-
-                // Clone the necessary variables so that they can be moved into the spawned task.
-                let urls_to_visit = self.urls_to_visit.clone();
-                let urls = self.urls.clone();
-                let client = self.client.clone();
-                let root = self.root.clone();
-
-                // Spawn a task to crawl the given URL
-                set.spawn(async move {
-                    // Obtain a permit - this will block if we've reached the upper limit of how many concurrent tasks we can have
-                    let _permit = permit;
-                    crawl_individual_url(path, client.clone(), urls, urls_to_visit, root.clone())
-                        .await;
-                });
+
+        loop {
+            // Wait for the next URL, but bail out as soon as the crawl is known to be finished.
+            let (url, depth) = tokio::select! {
+                maybe = rx.recv() => match maybe {
+                    Some(item) => item,
+                    None => break,
+                },
+                _ = done.notified() => break,
+            };
+
+            // Aquire a permit from the semaphore, which will block if the number of concurrent tasks has reached the limit.
+            let permit = Arc::clone(&sem).acquire_owned().await;
+
+            // Clone the necessary variables so that they can be moved into the spawned task.
+            let urls = self.urls.clone();
+            let client = self.client.clone();
+            let root = self.root.clone();
+            let robots = self.robots.clone();
+            let next_fetch = self.next_fetch.clone();
+            let subdomains = self.config.subdomains;
+            let crawl_delay = self.robots.crawl_delay;
+            let tx = tx.clone();
+            let seen = seen.clone();
+            let in_flight = in_flight.clone();
+            let done = done.clone();
+            let check = self.config.check;
+            let accepted = accepted.clone();
+            let link_results = link_results.clone();
+            let parents = parents.clone();
+            let extractors = extractors.clone();
+            let max_depth = self.config.max_depth;
+            let max_pages = self.config.max_pages;
+            let pages_fetched = pages_fetched.clone();
+
+            // Spawn a task to crawl the given URL
+            set.spawn(async move {
+                // Hold the permit the dispatcher acquired for the lifetime of this task; it is released when the task ends
+                let _permit = permit;
+                let label = url.as_str().to_string();
+                if let Err(e) = crawl_individual_url(
+                    url,
+                    depth,
+                    client.clone(),
+                    urls,
+                    tx,
+                    seen,
+                    in_flight.clone(),
+                    root.clone(),
+                    robots,
+                    next_fetch,
+                    subdomains,
+                    crawl_delay,
+                    check,
+                    accepted,
+                    link_results,
+                    parents,
+                    extractors,
+                    max_depth,
+                    max_pages,
+                    pages_fetched,
+                )
+                .await
+                {
+                    eprintln!("Error crawling {}: {}", label, e);
+                }
+                // This unit of work is done. If it was the last outstanding one, wake the
+                // dispatcher so it can shut down.
+                if in_flight.fetch_sub(1, Ordering::SeqCst) == 1 {
+                    done.notify_one();
+                }
+            });
+        }
+
+        // Drain any still-running workers before reporting results.
+        while set.join_next().await.is_some() {}
+
+        if self.config.check {
+            report_broken_links(&link_results, &parents);
+        } else {
+            self.write_output();
+        }
+    }
+
+    /// Serialize the crawled link graph in the configured format, to the `--output` file if one was
+    /// given or to stdout otherwise.
+    fn write_output(&self) {
+        let urls = self.urls.lock().expect("Could not obtain lock");
+        let result = match &self.config.output {
+            Some(path) => match std::fs::File::create(path) {
+                Ok(mut file) => write_graph(&urls, self.config.format, &mut file),
+                Err(e) => {
+                    eprintln!("Could not open output file {path}: {e}");
+                    return;
+                }
+            },
+            None => write_graph(&urls, self.config.format, &mut std::io::stdout().lock()),
+        };
+        if let Err(e) = result {
+            eprintln!("Could not write output: {e}");
+        }
+    }
+}
+
+/// Print a report of every link whose final status counted as broken, together with the set of
+/// pages that linked to it. Parent attribution is read from the inverse of the `urls` adjacency
+/// map, which every page populates as it is crawled.
+fn report_broken_links(link_results: &LinkResults, parents: &Parents) {
+    let link_results = link_results.lock().expect("Could not obtain lock");
+    let parents = parents.lock().expect("Could not obtain lock");
+    let mut broken: Vec<(&String, &CrawlError)> = link_results
+        .iter()
+        .filter_map(|(url, result)| result.as_ref().err().map(|e| (url, e)))
+        .collect();
+    broken.sort_by(|a, b| a.0.cmp(b.0));
+
+    if broken.is_empty() {
+        println!("No broken links found across {} checked links.", link_results.len());
+        return;
+    }
+    println!("Found {} broken link(s):", broken.len());
+    for (url, err) in broken {
+        println!("  {url} ({err})");
+        if let Some(linkers) = parents.get(url) {
+            let mut linkers: Vec<&String> = linkers.iter().collect();
+            linkers.sort();
+            for parent in linkers {
+                println!("    linked from {parent}");
             }
         }
-        println!("urls {:#?}", self.urls.lock().unwrap());
-        println!("length of urls {:#?}", self.urls.lock().unwrap().len());
     }
 }
 
+/// Resolve an extracted `href` against the page it was found on, returning a fully-qualified,
+/// canonicalized absolute URL. Relative links (`/foo`, `../foo`, `page.html`), protocol-relative
+/// links (`//cdn...`) and links carrying query strings or fragments are all handled by delegating
+/// to `Url::join`. Non-http(s) schemes (`mailto:`, `tel:`, `javascript:`, ...) are discarded by
+/// returning `None`, as are hrefs that fail to resolve against the base.
+fn resolve_url(base: &Url, href: &str) -> Option<Url> {
+    let joined = base.join(href).ok()?;
+    match joined.scheme() {
+        "http" | "https" => Some(canonicalize(joined)),
+        _ => None,
+    }
+}
+
+/// Canonicalize a URL so that links which point at the same resource dedup to a single key:
+/// fragments are stripped (they never reach the server) and a trailing slash is removed from
+/// non-root paths, so `/pages/` and `/pages` collapse to the same entry.
+fn canonicalize(mut url: Url) -> Url {
+    url.set_fragment(None);
+    let path = url.path().to_string();
+    if path.len() > 1 && path.ends_with('/') {
+        url.set_path(path.trim_end_matches('/'));
+    }
+    url
+}
+
+/// Decide whether `child` should be followed given the `root` we started from. By default only the
+/// exact root host is in scope; when `subdomains` is set we also follow hosts that share the root's
+/// registrable domain (approximated here as the final two labels, e.g. `blog.example.com` for a
+/// root of `example.com`).
+fn same_domain(child: &Url, root: &Url, subdomains: bool) -> bool {
+    let (child_host, root_host) = match (child.host_str(), root.host_str()) {
+        (Some(child_host), Some(root_host)) => (child_host, root_host),
+        _ => return false,
+    };
+    if child_host == root_host {
+        return true;
+    }
+    if !subdomains {
+        return false;
+    }
+    let registrable = |host: &str| {
+        host.rsplit('.')
+            .take(2)
+            .collect::<Vec<_>>()
+            .into_iter()
+            .rev()
+            .collect::<Vec<_>>()
+            .join(".")
+    };
+    registrable(child_host) == registrable(root_host)
+}
+
 /// This function is responsible for crawling an individual URL. It sends a GET request to the URL, and then parses the HTML response.
-/// It then extracts all the URLs from the HTML response, and adds them to the `urls_to_visit` vector if they are not already present.
+/// It then extracts all the URLs from the HTML response, and sends any newly-seen same-domain ones back onto the work channel.
 /// It also adds the URL to the `urls` hashmap, which maps a URL to all the URLs that it links to.
+///
+/// Newly-discovered URLs are gated through the shared `seen` set so nothing is enqueued twice, and
+/// each send bumps the `in_flight` counter (the worker that consumes the URL decrements it again)
+/// so the dispatcher can detect termination.
+///
+/// When `check` is set the crawler also behaves as a broken-link validator: off-domain links are
+/// not crawled but are still verified with a HEAD/GET, and every link's final status is recorded in
+/// `link_results` while `parents` tracks which pages linked to it.
+#[allow(clippy::too_many_arguments)]
 async fn crawl_individual_url(
-    path: String,
+    base: Url,
+    depth: usize,
     client: Client,
     urls: Arc<Mutex<HashMap<String, Vec<String>>>>,
-    urls_to_visit: Arc<Mutex<Vec<String>>>,
+    tx: UnboundedSender<(Url, usize)>,
+    seen: Arc<Mutex<HashSet<Url>>>,
+    in_flight: Arc<AtomicUsize>,
     root: String,
-) {
-    // If the path given is relative to the root (ie it starts with "/"), prepend it with the root
-    let url = if path.starts_with("/") {
-        root.clone() + &path
-    } else {
-        path.clone()
+    robots: Arc<Rules>,
+    next_fetch: Arc<Mutex<HashMap<String, Instant>>>,
+    subdomains: bool,
+    crawl_delay: Option<Duration>,
+    check: bool,
+    accepted: Arc<Vec<StatusCode>>,
+    link_results: LinkResults,
+    parents: Parents,
+    extractors: Arc<Vec<(String, String)>>,
+    max_depth: Option<usize>,
+    max_pages: Option<usize>,
+    pages_fetched: Arc<AtomicUsize>,
+) -> Result<(), CrawlError> {
+    // Parse the root URL so we can compare hosts for domain filtering.
+    let root_url = Url::parse(&root)?;
+
+    // Respect robots.txt for this URL itself, not just the links we discover on it: a disallowed
+    // URL - including the root we were seeded with - is never fetched.
+    let self_path = match base.query() {
+        Some(query) => format!("{}?{}", base.path(), query),
+        None => base.path().to_string(),
     };
+    if !robots.is_allowed(&self_path) {
+        return Ok(());
+    }
 
-    // Make get request to provided URL
-    let res = match client.get(url.clone()).header("User-Agent", "Mozilla/5.0 (iPad; CPU OS 12_2 like Mac OS X) AppleWebKit/605.1.15 (KHTML, like Gecko) Mobile/15E147").send().await {
-        Ok(res) => res,
-        Err(e) => {
-            println!("Error: {:#?}", e);
-            return;
+    // Honour a robots.txt Crawl-delay by spacing out fetches to the same host. We reserve a slot by
+    // advancing a shared per-host "next allowed fetch" time while holding the lock, so concurrent
+    // workers are handed staggered wait times (0, delay, 2*delay, ...) rather than all reading the
+    // same stale timestamp and firing together once the sleep elapses.
+    if let Some(delay) = crawl_delay {
+        if let Some(host) = base.host_str() {
+            let wait = {
+                let mut next_fetch = next_fetch.lock().expect("Could not obtain lock");
+                let now = Instant::now();
+                let next = next_fetch.get(host).copied().unwrap_or(now);
+                // Our slot starts no earlier than now; the one after ours is `delay` later.
+                let slot = next.max(now);
+                next_fetch.insert(host.to_string(), slot + delay);
+                slot.saturating_duration_since(now)
+            };
+            if !wait.is_zero() {
+                tokio::time::sleep(wait).await;
+            }
         }
-    };
-    // If the status code is not 200, return early
-    match res.status() {
-        reqwest::StatusCode::OK => (),
-        _ => return,
     }
-    // Extract the URLs from the HTML of the response
-    let extracted_urls = parse_resp_to_urls(res).await;
+
+    // Reserve a page slot atomically before fetching so `max_pages` is a hard cap: at most `max`
+    // fetches ever run, even with many workers in flight. If the cap is already met, bail without
+    // fetching and hand the reservation back.
+    if let Some(max) = max_pages {
+        if pages_fetched.fetch_add(1, Ordering::SeqCst) >= max {
+            pages_fetched.fetch_sub(1, Ordering::SeqCst);
+            return Ok(());
+        }
+    }
+
+    // Make get request to provided URL.
+    let res = client
+        .get(base.as_str())
+        .header("User-Agent", USER_AGENT)
+        .send()
+        .await?;
+    // Any 3xx redirects have already been followed by the client (up to the configured hop limit),
+    // so the status here is final. Collapse redirect chains to their target: the node we record is
+    // the URL the client actually landed on, not the one we requested.
+    let status = res.status();
+    let final_url = canonicalize(res.url().clone());
+    // In checker mode record this page's own status before deciding whether to walk it. We key it
+    // by the pre-fetch URL (`base`) so it matches the `parents` map, which records the link exactly
+    // as the linking page referenced it - otherwise a redirected-then-broken link would have no
+    // attributable parents in the report.
+    if check {
+        let result = classify(status, &accepted);
+        link_results
+            .lock()
+            .expect("Could not obtain lock")
+            .insert(base.as_str().to_string(), result);
+    }
+    // A non-OK page is a dead end as far as link extraction is concerned; the status is still
+    // recorded above so the checker can report it as broken.
+    if status != StatusCode::OK {
+        return classify(status, &accepted).map(|_| ());
+    }
+    // The resolved target is domain-filtered like any other link, so a redirect off-domain is
+    // dropped rather than crawled.
+    if !same_domain(&final_url, &root_url, subdomains) {
+        return Ok(());
+    }
+    let path = final_url.as_str().to_string();
+    // Extract the URLs from the HTML of the response, resolved against the page's final URL.
+    let extracted_urls = parse_resp_to_urls(res, &final_url, &extractors).await?;
+
+    // The depth the links we discover here would sit at, and whether we've hit the depth limit.
+    let child_depth = depth + 1;
+    let within_depth = max_depth.is_none_or(|max| child_depth <= max);
 
     for child_url in extracted_urls.iter() {
-        // Filter for URLs that have the same domain name as the root URL passed in
+        let child = child_url.as_str().to_string();
+        // Record the parent->child edge so a broken-link report can attribute each link to the
+        // pages that reference it.
+        if check {
+            parents
+                .lock()
+                .expect("Could not obtain lock")
+                .entry(child.clone())
+                .or_default()
+                .insert(path.clone());
+        }
 
-        if child_url.starts_with(&root) || child_url.starts_with("/") {
-            // Add the URL to the urls_to_visit object, unless its already present
-            if !urls
+        // URLs that live on the same host as the root (or a subdomain of it, when that's enabled)
+        // are crawled. Comparing hosts rather than string prefixes means `http://site` and
+        // `https://site` are treated alike. A path the site disallows in robots.txt is never
+        // enqueued.
+        let robots_path = match child_url.query() {
+            Some(query) => format!("{}?{}", child_url.path(), query),
+            None => child_url.path().to_string(),
+        };
+        let in_scope =
+            same_domain(child_url, &root_url, subdomains) && robots.is_allowed(&robots_path);
+
+        // Short-circuit new enqueues once we've reached the page cap (the hard cap is enforced by
+        // the reservation at fetch time above; this just avoids flooding the queue), and never
+        // enqueue past the depth limit.
+        let under_page_cap =
+            max_pages.is_none_or(|max| pages_fetched.load(Ordering::SeqCst) < max);
+
+        if in_scope && within_depth && under_page_cap {
+            // Only mark the URL seen once we're actually enqueuing it. If we marked a too-deep or
+            // over-cap sighting seen, a later in-range sighting reached via a shorter path would be
+            // wrongly skipped and `max_depth` would silently under-crawl.
+            let is_new = seen
                 .lock()
                 .expect("Could not obtain lock")
-                .contains_key(child_url)
-            {
-                urls_to_visit
+                .insert(child_url.clone());
+            if is_new {
+                // Bump the in-flight counter before sending so the dispatcher can't observe a
+                // transient zero and shut down early.
+                in_flight.fetch_add(1, Ordering::SeqCst);
+                if tx.send((child_url.clone(), child_depth)).is_err() {
+                    // The receiver is gone, so the crawl is shutting down; undo the bump.
+                    in_flight.fetch_sub(1, Ordering::SeqCst);
+                }
+            }
+        } else if check && !in_scope {
+            // Off-domain (or disallowed) link: in checker mode we verify it resolves without
+            // crawling it, and record the outcome. Gate on `seen` so each is checked only once.
+            let is_new = seen
+                .lock()
+                .expect("Could not obtain lock")
+                .insert(child_url.clone());
+            if is_new {
+                let result = check_link(&client, child_url, &accepted).await;
+                link_results
                     .lock()
                     .expect("Could not obtain lock")
-                    .push(child_url.clone());
+                    .insert(child.clone(), result);
             }
-            // Store the URL as a child URL for the parent URL in the urls object
-            urls.lock()
-                .expect("Could not obtain lock")
-                .entry(path.clone())
-                .or_insert(vec![])
-                .push(child_url.to_owned());
         }
+
+        // Store the URL as a child URL for the parent URL in the urls object.
+        urls.lock()
+            .expect("Could not obtain lock")
+            .entry(path.clone())
+            .or_insert(vec![])
+            .push(child);
     }
+    Ok(())
 }
 
-/// Takes a GET response, extracts the HTML from the text, filters for a tags (where hyperlinks are specified in HTML),
-/// filters for those which contain hrefs, and returns a vector of URLs as strings.
-async fn parse_resp_to_urls<'a>(res: Response) -> Vec<String> {
+/// Verify that an off-domain link resolves, without downloading its body. We issue a `HEAD` first
+/// (cheap) and fall back to `GET` for the servers that don't implement `HEAD`, returning the final
+/// status classified against the accepted set. Mirrors the HEAD-then-GET probe lychee uses.
+async fn check_link(
+    client: &Client,
+    url: &Url,
+    accepted: &[StatusCode],
+) -> Result<StatusCode, CrawlError> {
+    let head = client
+        .head(url.as_str())
+        .header("User-Agent", USER_AGENT)
+        .send()
+        .await;
+    let status = match head {
+        Ok(res) if res.status() != StatusCode::METHOD_NOT_ALLOWED => res.status(),
+        // HEAD unsupported or failed outright: retry with a GET.
+        _ => {
+            client
+                .get(url.as_str())
+                .header("User-Agent", USER_AGENT)
+                .send()
+                .await?
+                .status()
+        }
+    };
+    classify(status, accepted)
+}
+
+/// Takes a GET response, the URL it was fetched from, and the (selector, attribute) pairs to
+/// extract, pulls the matching attribute off every matching element, and returns a vector of
+/// fully-qualified, canonicalized URLs resolved against `base`. With the default `("a", "href")`
+/// pair this picks up hyperlinks; adding e.g. `("img", "src")` also collects image resources.
+async fn parse_resp_to_urls(
+    res: Response,
+    base: &Url,
+    extractors: &[(String, String)],
+) -> Result<Vec<Url>, CrawlError> {
+    let text = res.text().await?;
+    Ok(extract_links(&text, base, extractors))
+}
+
+/// Pull every link out of an HTML document for the given (selector, attribute) pairs, resolving
+/// each against `base`. Split out from [`parse_resp_to_urls`] so the extraction logic can be
+/// exercised without a live HTTP response.
+fn extract_links(html: &str, base: &Url, extractors: &[(String, String)]) -> Vec<Url> {
     let mut urls = vec![];
-    let text = res.text().await.unwrap();
-    let document = Html::parse_document(&text);
-    // Filter for HTML a tags, which define child hyperlinks
-    let selector = Selector::parse("a").unwrap();
-    for a_tag in document.select(&selector) {
-        let url = match a_tag.value().attr("href") {
-            Some(url) => url.to_string(),
-            None => continue,
+    let document = Html::parse_document(html);
+    for (selector, attr) in extractors {
+        // A selector that fails to parse is a configuration error, not a per-page one; skip it
+        // rather than aborting the crawl.
+        let parsed = match Selector::parse(selector) {
+            Ok(parsed) => parsed,
+            Err(e) => {
+                eprintln!("Ignoring invalid selector {selector:?}: {e}");
+                continue;
+            }
         };
-        urls.push(url);
+        for element in document.select(&parsed) {
+            let href = match element.value().attr(attr) {
+                Some(href) => href,
+                None => continue,
+            };
+            // Resolve the href against the page URL, discarding non-http(s) schemes and bad links.
+            if let Some(url) = resolve_url(base, href) {
+                urls.push(url);
+            }
+        }
     }
     urls
 }
@@ -211,46 +758,190 @@ mod tests {
     /// Note for this test and all others, we use the test website scrapethissite.com
     async fn test_single_url_crawl() {
         let client = Client::new();
-        let path = "https://www.scrapethissite.com/".to_string();
+        let base = Url::parse("https://www.scrapethissite.com/").unwrap();
         let urls = Arc::new(Mutex::new(HashMap::new()));
-        let urls_to_visit = Arc::new(Mutex::new(vec![]));
+        let (tx, mut rx) = mpsc::unbounded_channel::<(Url, usize)>();
+        // Seed the seen set with the page we're crawling, mirroring crawl_whole.
+        let seen = Arc::new(Mutex::new(HashSet::from([base.clone()])));
+        let in_flight = Arc::new(AtomicUsize::new(0));
         let root = "https://www.scrapethissite.com/".to_string();
-        crawl_individual_url(path, client, urls.clone(), urls_to_visit.clone(), root).await;
+        let robots = Arc::new(Rules::default());
+        let next_fetch = Arc::new(Mutex::new(HashMap::new()));
+        crawl_individual_url(
+            base,
+            0,
+            client,
+            urls.clone(),
+            tx.clone(),
+            seen,
+            in_flight,
+            root,
+            robots,
+            next_fetch,
+            false,
+            None,
+            false,
+            Arc::new(vec![]),
+            Arc::new(Mutex::new(HashMap::new())),
+            Arc::new(Mutex::new(HashMap::new())),
+            Arc::new(vec![("a".to_string(), "href".to_string())]),
+            None,
+            None,
+            Arc::new(AtomicUsize::new(0)),
+        )
+        .await
+        .expect("crawl should succeed");
+        // The urls map records every link found on the page, including duplicates. Links are now
+        // resolved to fully-qualified, canonicalized URLs rather than stored as the raw relative
+        // hrefs, so trailing slashes are normalized away on non-root paths.
         let expected = vec![
-            "/".to_string(),
-            "/pages/".to_string(),
-            "/lessons/".to_string(),
-            "/faq/".to_string(),
-            "/login/".to_string(),
-            "/pages/".to_string(),
-            "/lessons/".to_string(),
+            "https://www.scrapethissite.com/".to_string(),
+            "https://www.scrapethissite.com/pages".to_string(),
+            "https://www.scrapethissite.com/lessons".to_string(),
+            "https://www.scrapethissite.com/faq".to_string(),
+            "https://www.scrapethissite.com/login".to_string(),
+            "https://www.scrapethissite.com/pages".to_string(),
+            "https://www.scrapethissite.com/lessons".to_string(),
         ];
-        assert_eq!(
-            *urls_to_visit.lock().expect("couldn't obtain lock"),
-            expected.clone(),
-        );
         assert_eq!(
             *urls.lock().expect("couldn't obtain lock")["https://www.scrapethissite.com/"],
             expected
         );
+        // The work channel, by contrast, is deduped via the seen set: the root was already seen, so
+        // it is not re-enqueued, and each other link appears exactly once.
+        drop(tx);
+        let mut enqueued = vec![];
+        while let Some((url, depth)) = rx.recv().await {
+            // Every link discovered on the root page sits one hop away.
+            assert_eq!(depth, 1);
+            enqueued.push(url.as_str().to_string());
+        }
+        let expected_enqueued = vec![
+            "https://www.scrapethissite.com/pages".to_string(),
+            "https://www.scrapethissite.com/lessons".to_string(),
+            "https://www.scrapethissite.com/faq".to_string(),
+            "https://www.scrapethissite.com/login".to_string(),
+        ];
+        assert_eq!(enqueued, expected_enqueued);
+    }
+
+    #[tokio::test]
+    /// Test that non-default extraction selectors pull links off elements other than `<a href>`,
+    /// so configuring e.g. `img[src]` or `link[href]` actually changes what is discovered.
+    async fn test_custom_extractors() {
+        let base = Url::parse("https://example.com/").unwrap();
+        let html = r#"
+            <html><head><link rel="stylesheet" href="/style.css"></head>
+            <body>
+              <a href="/page">a link</a>
+              <img src="/logo.png">
+            </body></html>
+        "#;
+
+        // The default selector only follows anchors.
+        let default = extract_links(html, &base, &[("a".to_string(), "href".to_string())]);
+        let default: Vec<String> = default.iter().map(|u| u.as_str().to_string()).collect();
+        assert_eq!(default, vec!["https://example.com/page".to_string()]);
+
+        // Adding img[src] and link[href] picks up the other resources too.
+        let extractors = vec![
+            ("a".to_string(), "href".to_string()),
+            ("img".to_string(), "src".to_string()),
+            ("link".to_string(), "href".to_string()),
+        ];
+        let all = extract_links(html, &base, &extractors);
+        let all: Vec<String> = all.iter().map(|u| u.as_str().to_string()).collect();
+        assert_eq!(
+            all,
+            vec![
+                "https://example.com/page".to_string(),
+                "https://example.com/logo.png".to_string(),
+                "https://example.com/style.css".to_string(),
+            ]
+        );
     }
 
     #[tokio::test]
     /// Test that for a given webpage, we successfully extract the hrefs.
     async fn test_html_parsing() {
         let expected = vec![
-            "/".to_string(),
-            "/pages/".to_string(),
-            "/lessons/".to_string(),
-            "/faq/".to_string(),
-            "/login/".to_string(),
-            "/pages/".to_string(),
-            "/lessons/".to_string(),
+            "https://www.scrapethissite.com/".to_string(),
+            "https://www.scrapethissite.com/pages".to_string(),
+            "https://www.scrapethissite.com/lessons".to_string(),
+            "https://www.scrapethissite.com/faq".to_string(),
+            "https://www.scrapethissite.com/login".to_string(),
+            "https://www.scrapethissite.com/pages".to_string(),
+            "https://www.scrapethissite.com/lessons".to_string(),
         ];
         let client = Client::new();
         let path = "https://www.scrapethissite.com/".to_string();
+        let base = Url::parse(&path).unwrap();
         let res =  client.get(path.clone()).header("User-Agent", "Mozilla/5.0 (iPad; CPU OS 12_2 like Mac OS X) AppleWebKit/605.1.15 (KHTML, like Gecko) Mobile/15E147").send().await.unwrap();
-        let ans = parse_resp_to_urls(res).await;
+        let extractors = vec![("a".to_string(), "href".to_string())];
+        let ans = parse_resp_to_urls(res, &base, &extractors).await.unwrap();
+        let ans: Vec<String> = ans.iter().map(|u| u.as_str().to_string()).collect();
         assert_eq!(ans, expected);
     }
+
+    /// Regression test for the producer/consumer shape in `crawl_whole`: a single dispatcher gated
+    /// on a semaphore, workers that `send` discovered work back onto the queue while holding their
+    /// permit, and an in-flight counter to detect termination. The fan-out here (500 children) far
+    /// exceeds the concurrency limit (8). If the queue were a bounded channel sized to the permit
+    /// count - as it once was - every worker would block mid-`send` holding a permit, the dispatcher
+    /// could never acquire one to drain the queue, and this test would hang. With the unbounded
+    /// queue it completes, visiting every node exactly once.
+    #[tokio::test]
+    async fn test_fanout_exceeds_concurrency_limit() {
+        // An in-memory graph standing in for fetched pages: the root (0) links to 500 leaves.
+        let fanout = 500usize;
+        let concurrency = 8;
+
+        let sem = Arc::new(Semaphore::new(concurrency));
+        let (tx, mut rx) = mpsc::unbounded_channel::<usize>();
+        let seen = Arc::new(Mutex::new(HashSet::from([0usize])));
+        let visited = Arc::new(AtomicUsize::new(0));
+        let in_flight = Arc::new(AtomicUsize::new(0));
+        let done = Arc::new(Notify::new());
+
+        in_flight.fetch_add(1, Ordering::SeqCst);
+        tx.send(0).expect("queue unexpectedly closed");
+
+        let mut set = JoinSet::new();
+        loop {
+            let node = tokio::select! {
+                maybe = rx.recv() => match maybe {
+                    Some(node) => node,
+                    None => break,
+                },
+                _ = done.notified() => break,
+            };
+            let permit = Arc::clone(&sem).acquire_owned().await;
+            let tx = tx.clone();
+            let seen = seen.clone();
+            let visited = visited.clone();
+            let in_flight = in_flight.clone();
+            let done = done.clone();
+            set.spawn(async move {
+                let _permit = permit;
+                visited.fetch_add(1, Ordering::SeqCst);
+                // Only the root has children; each leaf is terminal.
+                if node == 0 {
+                    for child in 1..=fanout {
+                        if seen.lock().expect("lock").insert(child) {
+                            in_flight.fetch_add(1, Ordering::SeqCst);
+                            // Holding the permit across the send is exactly what deadlocked the
+                            // bounded-channel version.
+                            let _ = tx.send(child);
+                        }
+                    }
+                }
+                if in_flight.fetch_sub(1, Ordering::SeqCst) == 1 {
+                    done.notify_one();
+                }
+            });
+        }
+        while set.join_next().await.is_some() {}
+
+        assert_eq!(visited.load(Ordering::SeqCst), fanout + 1);
+    }
 }
@@ -0,0 +1,65 @@
+//! Serialization of the crawled link graph. The crawler records a parent->children adjacency map;
+//! this module writes it out in one of a few formats so it can be consumed downstream: `Text` (the
+//! original debug dump), `Json` (via serde, for machine consumption) and `Dot` (Graphviz, so the
+//! site's link graph can be rendered with `dot`).
+
+use std::collections::{HashMap, HashSet};
+use std::io::{self, Write};
+
+/// The format to serialize the link graph in, selected with `--format`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    Text,
+    Json,
+    Dot,
+}
+
+impl OutputFormat {
+    /// Parse the value of the `--format` flag, returning `None` for anything we don't recognise.
+    pub fn parse(value: &str) -> Option<OutputFormat> {
+        match value {
+            "text" => Some(OutputFormat::Text),
+            "json" => Some(OutputFormat::Json),
+            "dot" => Some(OutputFormat::Dot),
+            _ => None,
+        }
+    }
+}
+
+/// Write the parent->children `urls` map to `writer` in the requested `format`.
+pub fn write_graph(
+    urls: &HashMap<String, Vec<String>>,
+    format: OutputFormat,
+    writer: &mut impl Write,
+) -> io::Result<()> {
+    match format {
+        OutputFormat::Text => write!(writer, "urls {urls:#?}\nlength of urls {:#?}\n", urls.len()),
+        OutputFormat::Json => {
+            serde_json::to_writer_pretty(&mut *writer, urls)
+                .map_err(io::Error::other)?;
+            writeln!(writer)
+        }
+        OutputFormat::Dot => write_dot(urls, writer),
+    }
+}
+
+/// Emit the graph as Graphviz DOT: one `"parent" -> "child";` edge per link. Duplicate edges (a
+/// page that links to the same target more than once) are collapsed so each edge is drawn once.
+fn write_dot(urls: &HashMap<String, Vec<String>>, writer: &mut impl Write) -> io::Result<()> {
+    writeln!(writer, "digraph crawl {{")?;
+    let mut seen_edges: HashSet<(&str, &str)> = HashSet::new();
+    for (parent, children) in urls {
+        for child in children {
+            if seen_edges.insert((parent, child)) {
+                writeln!(writer, "    {} -> {};", quote(parent), quote(child))?;
+            }
+        }
+    }
+    writeln!(writer, "}}")
+}
+
+/// Quote a URL as a DOT identifier, escaping the characters that are significant inside a
+/// double-quoted string.
+fn quote(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
@@ -0,0 +1,153 @@
+//! A small robots.txt subsystem. We fetch `/robots.txt` for the root host once at startup and
+//! compile the directives that apply to our own user-agent into a [`Rules`] value, which the
+//! crawler then consults before enqueuing or fetching any URL. Only the handful of directives we
+//! actually act on are modelled: `User-agent`, `Disallow`, `Allow` and `Crawl-delay`.
+
+use reqwest::Client;
+use std::time::Duration;
+use url::Url;
+
+/// Whether a robots.txt `User-agent` group token selects our crawler: per RFC 9309 the comparison
+/// is case-insensitive and a group token identifies us when it is a prefix of our product token
+/// (e.g. a `Crawler` group matches a `crawler/1.0` token).
+fn token_matches(group_token: &str, product_token: &str) -> bool {
+    product_token
+        .to_lowercase()
+        .starts_with(&group_token.to_lowercase())
+}
+
+/// The compiled set of rules that apply to our user-agent, as selected from a robots.txt file.
+/// An empty `disallow` list (the common "allow everything" case, and what we fall back to when a
+/// site has no robots.txt) means every path is permitted.
+#[derive(Debug, Default, Clone)]
+pub struct Rules {
+    allow: Vec<String>,
+    disallow: Vec<String>,
+    /// The `Crawl-delay` for our user-agent, if one was specified.
+    pub crawl_delay: Option<Duration>,
+}
+
+impl Rules {
+    /// Fetch and parse `/robots.txt` for the host of `root`. The request is sent with `ua_header`
+    /// (the browser string we masquerade as), but group selection matches our own `product_token`
+    /// rather than the browser UA, falling back to the `*` group. A missing or unreadable
+    /// robots.txt is treated as "allow everything", matching how well-behaved crawlers degrade.
+    pub async fn fetch(client: &Client, root: &Url, ua_header: &str, product_token: &str) -> Rules {
+        let robots_url = match root.join("/robots.txt") {
+            Ok(robots_url) => robots_url,
+            Err(_) => return Rules::default(),
+        };
+        let text = match client
+            .get(robots_url.as_str())
+            .header("User-Agent", ua_header)
+            .send()
+            .await
+        {
+            Ok(res) if res.status().is_success() => res.text().await.unwrap_or_default(),
+            // No robots.txt, or we couldn't reach it - default to permitting everything.
+            _ => return Rules::default(),
+        };
+        Rules::parse(&text, product_token)
+    }
+
+    /// Parse a robots.txt body, returning the [`Rules`] for our `product_token`. The rules of every
+    /// group headed by a token that is a case-insensitive prefix of our product token are merged
+    /// together, and win over the wildcard `*` group, which applies only when no such group exists.
+    pub fn parse(text: &str, product_token: &str) -> Rules {
+        // A group being accumulated: the user-agents it applies to, plus its rules so far.
+        let mut current_agents: Vec<String> = vec![];
+        let mut specific: Option<Rules> = None;
+        let mut wildcard: Option<Rules> = None;
+        // Whether the previous non-blank line was a `User-agent`, so that consecutive
+        // `User-agent` lines are grouped together as sharing the rules that follow.
+        let mut last_was_agent = false;
+
+        // Append a directive to whichever in-scope groups the current user-agents select. A group
+        // token matches us only when it is a case-insensitive prefix of our product token (RFC
+        // 9309 semantics), so a group headed `User-agent: Mobile` is not wrongly matched against
+        // substrings of the browser UA we present on requests.
+        let apply = |agents: &[String],
+                     specific: &mut Option<Rules>,
+                     wildcard: &mut Option<Rules>,
+                     f: &dyn Fn(&mut Rules)| {
+            for agent in agents {
+                if agent == "*" {
+                    f(wildcard.get_or_insert_with(Rules::default));
+                } else if token_matches(agent, product_token) {
+                    f(specific.get_or_insert_with(Rules::default));
+                }
+            }
+        };
+
+        for line in text.lines() {
+            // Strip comments and surrounding whitespace.
+            let line = line.split('#').next().unwrap_or("").trim();
+            let (field, value) = match line.split_once(':') {
+                Some((field, value)) => (field.trim().to_lowercase(), value.trim().to_string()),
+                None => continue,
+            };
+            match field.as_str() {
+                "user-agent" => {
+                    // A User-agent line after a rule line starts a fresh group.
+                    if !last_was_agent {
+                        current_agents.clear();
+                    }
+                    current_agents.push(value);
+                    last_was_agent = true;
+                }
+                "disallow" => {
+                    last_was_agent = false;
+                    if value.is_empty() {
+                        continue;
+                    }
+                    apply(&current_agents, &mut specific, &mut wildcard, &|rules| {
+                        rules.disallow.push(value.clone())
+                    });
+                }
+                "allow" => {
+                    last_was_agent = false;
+                    if value.is_empty() {
+                        continue;
+                    }
+                    apply(&current_agents, &mut specific, &mut wildcard, &|rules| {
+                        rules.allow.push(value.clone())
+                    });
+                }
+                "crawl-delay" => {
+                    last_was_agent = false;
+                    if let Ok(secs) = value.parse::<f64>() {
+                        let delay = Duration::from_secs_f64(secs);
+                        apply(&current_agents, &mut specific, &mut wildcard, &|rules| {
+                            rules.crawl_delay = Some(delay)
+                        });
+                    }
+                }
+                _ => last_was_agent = false,
+            }
+        }
+
+        // Prefer the group that named our product token explicitly over the wildcard group.
+        specific.or(wildcard).unwrap_or_default()
+    }
+
+    /// Decide whether `path` (a URL path, optionally with query) may be fetched. Following the
+    /// usual longest-match semantics, the longest matching rule wins and `Allow` beats `Disallow`
+    /// on ties; paths with no matching rule are permitted.
+    pub fn is_allowed(&self, path: &str) -> bool {
+        let mut best_len = 0;
+        let mut allowed = true;
+        for rule in &self.disallow {
+            if path.starts_with(rule) && rule.len() > best_len {
+                best_len = rule.len();
+                allowed = false;
+            }
+        }
+        for rule in &self.allow {
+            if path.starts_with(rule) && rule.len() >= best_len {
+                best_len = rule.len();
+                allowed = true;
+            }
+        }
+        allowed
+    }
+}